@@ -43,6 +43,18 @@ pub struct VisibleImageTile {
     pub local_clip_rect: LayoutRect,
 }
 
+/// One tile of a single decoded YUV plane, analogous to `VisibleImageTile` on
+/// the RGBA path. `local_rect` is in the same layout space for every plane of
+/// a given primitive; chroma-subsampled planes are sampled from a smaller
+/// source rect but still cover the same `local_rect` on screen.
+#[derive(Debug, MallocSizeOf)]
+#[cfg_attr(feature = "capture", derive(Serialize))]
+#[cfg_attr(feature = "replay", derive(Deserialize))]
+pub struct VisibleYuvPlaneTile {
+    pub task_id: RenderTaskId,
+    pub local_rect: LayoutRect,
+}
+
 // Key that identifies a unique (partial) image that is being
 // stored in the render task cache.
 #[derive(Debug, Copy, Clone, Eq, Hash, PartialEq)]
@@ -593,16 +605,51 @@ impl AdjustedImageSource {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// The transfer function (EOTF) the YUV samples were encoded with. SDR content
+/// is gamma-encoded per sRGB/BT.709 and needs no extra shader work, while HDR
+/// content is encoded with a perceptual or hybrid log-gamma curve and needs to
+/// be linearized and tone-mapped down to the display's capabilities before use.
+#[cfg_attr(feature = "capture", derive(Serialize))]
+#[cfg_attr(feature = "replay", derive(Deserialize))]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, MallocSizeOf)]
+pub enum TransferFunction {
+    /// sRGB / BT.709 gamma - the SDR fast path, no inverse EOTF in the shader.
+    Srgb,
+    /// SMPTE ST 2084 (PQ), used by most 10/12-bit HDR10 video.
+    Pq,
+    /// ARIB STD-B67 (HLG), used by broadcast-oriented HDR video.
+    Hlg,
+}
+
+/// The index within `yuv_key` / `src_yuv` of the optional alpha plane used by
+/// formats such as `YuvFormat::YUVA` (e.g. VP9/AV1 video decoded with a
+/// separate per-pixel coverage plane). The plane is only sampled when
+/// `format.get_plane_num()` reports more than `YUV_ALPHA_PLANE_INDEX` planes.
+const YUV_ALPHA_PLANE_INDEX: usize = 3;
+
 #[cfg_attr(feature = "capture", derive(Serialize))]
 #[cfg_attr(feature = "replay", derive(Deserialize))]
 #[derive(Debug, Clone, Eq, MallocSizeOf, PartialEq, Hash)]
 pub struct YuvImage {
     pub color_depth: ColorDepth,
-    pub yuv_key: [ApiImageKey; 3],
+    // The 4th entry is only populated for alpha-carrying formats (YUVA).
+    pub yuv_key: [ApiImageKey; 4],
     pub format: YuvFormat,
     pub color_space: YuvColorSpace,
     pub color_range: ColorRange,
     pub image_rendering: ImageRendering,
+    pub transfer_function: TransferFunction,
+    /// Content mastering max luminance, in nits. Only meaningful for HDR transfer
+    /// functions; ignored on the `Srgb` fast path. Stored as an integer (like
+    /// `ColorU` vs `ColorF` elsewhere in this file) since the key needs `Eq`/`Hash`.
+    pub content_max_luminance: u32,
+    /// Target display max luminance, in nits, that HDR content should be tone
+    /// mapped down to.
+    pub target_max_luminance: u32,
+    /// An optional sub-region of each decoded plane to sample from, in texels
+    /// of the (unsubsampled) luma plane. Used for sprite-sheet-style video
+    /// atlases and for cropping letterboxed frames.
+    pub sub_rect: Option<DeviceIntRect>,
 }
 
 pub type YuvImageKey = PrimKey<YuvImage>;
@@ -626,12 +673,19 @@ impl InternDebug for YuvImageKey {}
 #[derive(MallocSizeOf)]
 pub struct YuvImageData {
     pub color_depth: ColorDepth,
-    pub yuv_key: [ApiImageKey; 3],
-    pub src_yuv: [Option<RenderTaskId>; 3],
+    pub yuv_key: [ApiImageKey; 4],
+    /// One entry per plane. Usually holds a single tile covering the whole
+    /// primitive; holds more than one once the plane is too large for the
+    /// driver's max texture size and had to be decomposed (see `update`).
+    pub src_yuv: [Vec<VisibleYuvPlaneTile>; 4],
     pub format: YuvFormat,
     pub color_space: YuvColorSpace,
     pub color_range: ColorRange,
     pub image_rendering: ImageRendering,
+    pub transfer_function: TransferFunction,
+    pub content_max_luminance: f32,
+    pub target_max_luminance: f32,
+    pub sub_rect: Option<DeviceIntRect>,
 }
 
 impl From<YuvImage> for YuvImageData {
@@ -639,11 +693,174 @@ impl From<YuvImage> for YuvImageData {
         YuvImageData {
             color_depth: image.color_depth,
             yuv_key: image.yuv_key,
-            src_yuv: [None, None, None],
+            src_yuv: [Vec::new(), Vec::new(), Vec::new(), Vec::new()],
             format: image.format,
             color_space: image.color_space,
             color_range: image.color_range,
             image_rendering: image.image_rendering,
+            transfer_function: image.transfer_function,
+            content_max_luminance: image.content_max_luminance as f32,
+            target_max_luminance: image.target_max_luminance as f32,
+            sub_rect: image.sub_rect,
+        }
+    }
+}
+
+/// Returns the (x, y) subsampling factor for a given plane of `format`, i.e. how
+/// many luma samples correspond to one sample of that plane. The luma (and the
+/// alpha plane, when present) are always full resolution; chroma planes are
+/// subsampled per the format (e.g. 4:2:0 halves both axes).
+fn channel_subsampling(format: YuvFormat, channel: usize) -> (f32, f32) {
+    if channel == 0 || channel == YUV_ALPHA_PLANE_INDEX {
+        (1.0, 1.0)
+    } else {
+        format.get_subsampling_factor()
+    }
+}
+
+/// Scales a sub-rect given in full (luma) resolution texels down into the
+/// space of a plane that is subsampled by `subsampling`.
+fn scale_sub_rect(sub_rect: DeviceIntRect, subsampling: (f32, f32)) -> DeviceIntRect {
+    DeviceIntRect::from_origin_and_size(
+        point2(
+            (sub_rect.min.x as f32 / subsampling.0) as i32,
+            (sub_rect.min.y as f32 / subsampling.1) as i32,
+        ),
+        DeviceIntSize::new(
+            (sub_rect.width() as f32 / subsampling.0) as i32,
+            (sub_rect.height() as f32 / subsampling.1) as i32,
+        ),
+    )
+}
+
+/// Requests a single YUV plane and decomposes it into render tasks, one per
+/// tile, analogous to the RGBA tiling path in `ImageData::update`. Most planes
+/// fit in a single texture and this returns a one-element `Vec`; planes larger
+/// than the driver's max texture size are decomposed into several tiles whose
+/// rects are scaled back up from plane space to the primitive's layout space.
+/// When `sub_rect` is set, the plane is routed through the render task cache
+/// instead, keyed on the (subsampling-scaled) texel rect to crop.
+fn request_yuv_plane_tiles(
+    key: ApiImageKey,
+    rendering: ImageRendering,
+    subsampling: (f32, f32),
+    sub_rect: Option<DeviceIntRect>,
+    prim_rect: LayoutRect,
+    prim_spatial_node_index: SpatialNodeIndex,
+    frame_state: &mut FrameBuildingState,
+    frame_context: &FrameBuildingContext,
+    visibility: &PrimitiveVisibility,
+) -> Vec<VisibleYuvPlaneTile> {
+    let image_properties = frame_state.resource_cache.get_image_properties(key);
+
+    // Tiling and sub-rect cropping don't currently compose; a tiled plane is
+    // always sampled in full. In practice oversized video and cropped
+    // sprite-sheet atlases are mutually exclusive use cases.
+    debug_assert!(
+        sub_rect.is_none() || !matches!(image_properties, Some(ImageProperties { tiling: Some(_), .. })),
+        "sub_rect cropping of a tiled YUV plane is not supported",
+    );
+
+    let plane_rect = LayoutRect::from_origin_and_size(
+        prim_rect.min,
+        LayoutSize::new(
+            prim_rect.width() / subsampling.0,
+            prim_rect.height() / subsampling.1,
+        ),
+    );
+
+    match image_properties {
+        Some(ImageProperties { tiling: Some(tile_size), visible_rect, .. }) => {
+            let mut tiles = Vec::new();
+
+            // Narrow the blob's raw `visible_rect` down to what's actually on
+            // screen and dirty this frame, the same way the RGBA tiling path
+            // does in `ImageData::update`, so oversized video doesn't allocate
+            // a render task for every tile every frame.
+            let culled_visible_rect = compute_conservative_visible_rect(
+                &visibility.clip_chain,
+                frame_state.current_dirty_region().combined,
+                frame_state.current_dirty_region().visibility_spatial_node,
+                prim_spatial_node_index,
+                frame_context.spatial_tree,
+            );
+            // `plane_rect` is `prim_rect` shrunk by the plane's subsampling, so
+            // bring the culled rect into the same space before tiling.
+            let culled_plane_rect = LayoutRect::from_origin_and_size(
+                point2(
+                    prim_rect.min.x + (culled_visible_rect.min.x - prim_rect.min.x) / subsampling.0,
+                    prim_rect.min.y + (culled_visible_rect.min.y - prim_rect.min.y) / subsampling.1,
+                ),
+                LayoutSize::new(
+                    culled_visible_rect.width() / subsampling.0,
+                    culled_visible_rect.height() / subsampling.1,
+                ),
+            );
+
+            for tile in image_tiling::tiles(&plane_rect, &culled_plane_rect, &visible_rect, tile_size as i32) {
+                let request = ImageRequest { key, rendering, tile: Some(tile.offset) };
+                let size = frame_state.resource_cache.request_image(request, frame_state.gpu_cache);
+                let task_id = frame_state.rg_builder.add().init(RenderTask::new_image(size, request));
+
+                // Scale the tile's rect back up so every plane's tiles describe
+                // the same on-screen region, regardless of chroma subsampling.
+                let local_rect = LayoutRect::from_origin_and_size(
+                    point2(
+                        prim_rect.min.x + (tile.rect.min.x - prim_rect.min.x) * subsampling.0,
+                        prim_rect.min.y + (tile.rect.min.y - prim_rect.min.y) * subsampling.1,
+                    ),
+                    LayoutSize::new(tile.rect.width() * subsampling.0, tile.rect.height() * subsampling.1),
+                );
+
+                tiles.push(VisibleYuvPlaneTile { task_id, local_rect });
+            }
+
+            tiles
+        }
+        Some(ImageProperties { ref descriptor, .. }) if sub_rect.is_some() => {
+            let request = ImageRequest { key, rendering, tile: None };
+            let size = frame_state.resource_cache.request_image(request, frame_state.gpu_cache);
+            let task_id = frame_state.rg_builder.add().init(RenderTask::new_image(size, request));
+
+            // Scale the caller-provided (full-resolution) sub-rect down into this
+            // plane's (possibly subsampled) texel space.
+            let texel_rect = sub_rect.map(|rect| scale_sub_rect(rect, subsampling));
+            let cropped_size = texel_rect.unwrap().size();
+
+            let image_cache_key = ImageCacheKey { request, texel_rect };
+
+            // Every frame we need to request the render task cache item; the
+            // closure only runs the first time through, or after eviction.
+            let cached_task_handle = frame_state.resource_cache.request_render_task(
+                Some(RenderTaskCacheKey {
+                    size: cropped_size,
+                    kind: RenderTaskCacheKeyKind::Image(image_cache_key),
+                }),
+                descriptor.is_opaque(),
+                RenderTaskParent::Surface,
+                frame_state.gpu_cache,
+                &mut frame_state.frame_gpu_data.f32,
+                frame_state.rg_builder,
+                &mut frame_state.surface_builder,
+                &mut |rg_builder, _, _| {
+                    // Blit just the cropped sub-rect out of the decoded plane and
+                    // into its own persistent cache entry.
+                    RenderTask::new_blit(
+                        cropped_size,
+                        task_id,
+                        texel_rect.unwrap(),
+                        rg_builder,
+                    )
+                },
+            );
+
+            vec![VisibleYuvPlaneTile { task_id: cached_task_handle, local_rect: prim_rect }]
+        }
+        _ => {
+            let request = ImageRequest { key, rendering, tile: None };
+            let size = frame_state.resource_cache.request_image(request, frame_state.gpu_cache);
+            let task_id = frame_state.rg_builder.add().init(RenderTask::new_image(size, request));
+            vec![VisibleYuvPlaneTile { task_id, local_rect: prim_rect }]
         }
     }
 }
@@ -653,69 +870,156 @@ impl YuvImageData {
     /// times per frame, by each primitive reference that refers to this interned
     /// template. The initial request call to the GPU cache ensures that work is only
     /// done if the cache entry is invalid (due to first use or eviction).
+    ///
+    /// `is_overlay_candidate` is set by the caller once it has determined that this
+    /// primitive covers a simple axis-aligned region with nothing else drawn over it,
+    /// which is a prerequisite for promoting to a native compositor overlay. When we
+    /// do promote, the planes are handed to the OS compositor directly, so we skip
+    /// allocating the per-plane render tasks that the `Blit` path requires.
     pub fn update(
         &mut self,
         common: &mut PrimTemplateCommonData,
+        compositor_surface_kind: &mut CompositorSurfaceKind,
+        is_overlay_candidate: bool,
+        prim_spatial_node_index: SpatialNodeIndex,
         frame_state: &mut FrameBuildingState,
+        frame_context: &FrameBuildingContext,
+        visibility: &mut PrimitiveVisibility,
     ) {
 
-        self.src_yuv = [ None, None, None ];
+        // Pick up a color depth override carried on the decoded image, if the
+        // decoder supplied one (e.g. a 10/12-bit VP9/AV1 frame).
+        //
+        // TODO(chunk1-3): `transfer_function` should be refreshed the same way
+        // once a decoded frame can carry PQ/HLG metadata this far; today
+        // `ImageDescriptor` has no such field, so it's never updated past what
+        // was set at display-list build time (defaulting new content to the
+        // `Srgb` fast path). That's a real gap against this request and needs
+        // either a descriptor field to carry it or another plumbing path from
+        // the decoder before HDR streams can change transfer function mid-playback.
+        if let Some(properties) = frame_state.resource_cache.get_image_properties(self.yuv_key[0]) {
+            self.color_depth = properties.descriptor.color_depth;
+        }
 
         let channel_num = self.format.get_plane_num();
-        debug_assert!(channel_num <= 3);
-        for channel in 0 .. channel_num {
-            let request = ImageRequest {
-                key: self.yuv_key[channel],
-                rendering: self.image_rendering,
-                tile: None,
-            };
-
-            let size = frame_state.resource_cache.request_image(
-                request,
-                frame_state.gpu_cache,
-            );
+        debug_assert!(channel_num <= 4);
+
+        // Alpha-carrying formats always need to be composited by us, since the
+        // OS compositor has no notion of the extra coverage plane. Likewise, a
+        // `sub_rect` crop has to stay on the `Blit` path: the overlay path hands
+        // the full decoded planes straight to the OS compositor with no way to
+        // apply a source rect, so a cropped or sprite-sheet-atlas primitive must
+        // never be promoted.
+        *compositor_surface_kind = if is_overlay_candidate
+            && channel_num <= YUV_ALPHA_PLANE_INDEX
+            && self.sub_rect.is_none() {
+            CompositorSurfaceKind::Overlay
+        } else {
+            CompositorSurfaceKind::Blit
+        };
 
-            let task_id = frame_state.rg_builder.add().init(
-                RenderTask::new_image(size, request)
-            );
+        if *compositor_surface_kind == CompositorSurfaceKind::Blit {
+            let mut any_tiles = false;
+
+            for channel in 0 .. channel_num {
+                self.src_yuv[channel] = request_yuv_plane_tiles(
+                    self.yuv_key[channel],
+                    self.image_rendering,
+                    channel_subsampling(self.format, channel),
+                    self.sub_rect,
+                    common.prim_rect,
+                    prim_spatial_node_index,
+                    frame_state,
+                    frame_context,
+                    visibility,
+                );
+                any_tiles |= !self.src_yuv[channel].is_empty();
+            }
 
-            self.src_yuv[channel] = Some(task_id);
+            if !any_tiles {
+                // All planes' tiles were culled against the current dirty/visible
+                // region; mirror the RGBA tiling path and mark the primitive
+                // invisible rather than leaving every plane's tile list empty.
+                visibility.reset();
+            }
+        } else {
+            for channel in 0 .. channel_num {
+                self.src_yuv[channel].clear();
+            }
         }
 
         if let Some(mut request) = frame_state.gpu_cache.request(&mut common.gpu_cache_handle) {
             self.write_prim_gpu_blocks(&mut request);
         };
 
-        // YUV images never have transparency
-        common.opacity = PrimitiveOpacity::opaque();
+        // YUV images are opaque, unless a 4th (alpha) plane was decoded
+        // alongside the luma/chroma planes.
+        common.opacity = if channel_num > YUV_ALPHA_PLANE_INDEX {
+            PrimitiveOpacity::translucent()
+        } else {
+            PrimitiveOpacity::opaque()
+        };
     }
 
     pub fn request_resources(
         &mut self,
+        prim_rect: LayoutRect,
         resource_cache: &mut ResourceCache,
         gpu_cache: &mut GpuCache,
     ) {
         let channel_num = self.format.get_plane_num();
-        debug_assert!(channel_num <= 3);
+        debug_assert!(channel_num <= 4);
         for channel in 0 .. channel_num {
-            resource_cache.request_image(
-                ImageRequest {
-                    key: self.yuv_key[channel],
-                    rendering: self.image_rendering,
-                    tile: None,
-                },
-                gpu_cache,
-            );
+            let key = self.yuv_key[channel];
+            let subsampling = channel_subsampling(self.format, channel);
+
+            match resource_cache.get_image_properties(key) {
+                Some(ImageProperties { tiling: Some(tile_size), visible_rect, .. }) => {
+                    let plane_rect = LayoutRect::from_origin_and_size(
+                        prim_rect.min,
+                        LayoutSize::new(
+                            prim_rect.width() / subsampling.0,
+                            prim_rect.height() / subsampling.1,
+                        ),
+                    );
+
+                    for tile in image_tiling::tiles(&plane_rect, &plane_rect, &visible_rect, tile_size as i32) {
+                        resource_cache.request_image(
+                            ImageRequest { key, rendering: self.image_rendering, tile: Some(tile.offset) },
+                            gpu_cache,
+                        );
+                    }
+                }
+                _ => {
+                    resource_cache.request_image(
+                        ImageRequest { key, rendering: self.image_rendering, tile: None },
+                        gpu_cache,
+                    );
+                }
+            }
         }
     }
 
     pub fn write_prim_gpu_blocks(&self, request: &mut GpuDataRequest) {
         let ranged_color_space = self.color_space.with_range(self.color_range);
+        let has_alpha_plane = self.format.get_plane_num() > YUV_ALPHA_PLANE_INDEX;
+        // The has-alpha flag and the transfer function discriminant share this
+        // float: both are small, rarely-changing bitfields and we only have the
+        // one spare slot left in the first block.
+        let packed_flags = (self.transfer_function as u32) | ((has_alpha_plane as u32) << 8);
         request.push([
             pack_as_float(self.color_depth.bit_depth()),
             pack_as_float(ranged_color_space as u32),
             pack_as_float(self.format as u32),
-            0.0
+            pack_as_float(packed_flags),
+        ]);
+        // Tone-mapping metadata for HDR transfer functions. The shader only reads
+        // this when `transfer_function != Srgb`; SDR content keeps its fast path.
+        request.push([
+            self.content_max_luminance,
+            self.target_max_luminance,
+            0.0,
+            0.0,
         ]);
     }
 }
@@ -755,6 +1059,9 @@ impl InternablePrimitive for YuvImage {
         data_handle: YuvImageDataHandle,
         _prim_store: &mut PrimitiveStore,
     ) -> PrimitiveInstanceKind {
+        // `Blit` is just the initial value here; `YuvImageData::update` re-evaluates
+        // the compositor surface kind every frame and promotes to `Overlay` once the
+        // primitive is known to be a simple, axis-aligned, fully opaque surface.
         PrimitiveInstanceKind::YuvImage {
             data_handle,
             segment_instance_index: SegmentInstanceIndex::INVALID,
@@ -782,7 +1089,7 @@ fn test_struct_sizes() {
     assert_eq!(mem::size_of::<Image>(), 32, "Image size changed");
     assert_eq!(mem::size_of::<ImageTemplate>(), 72, "ImageTemplate size changed");
     assert_eq!(mem::size_of::<ImageKey>(), 52, "ImageKey size changed");
-    assert_eq!(mem::size_of::<YuvImage>(), 32, "YuvImage size changed");
-    assert_eq!(mem::size_of::<YuvImageTemplate>(), 84, "YuvImageTemplate size changed");
-    assert_eq!(mem::size_of::<YuvImageKey>(), 52, "YuvImageKey size changed");
+    assert_eq!(mem::size_of::<YuvImage>(), 72, "YuvImage size changed");
+    assert_eq!(mem::size_of::<YuvImageTemplate>(), 200, "YuvImageTemplate size changed");
+    assert_eq!(mem::size_of::<YuvImageKey>(), 92, "YuvImageKey size changed");
 }